@@ -4,38 +4,71 @@
 // Author: Joshua Holmes
 //
 
+extern crate zip;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+extern crate serde_json;
+
 use std::str;
 use std::env;
+use std::path::Path;
 
+mod licensee;
+mod mbc;
 mod rom;
 mod util;
 
 use rom::Rom;
 
 fn main() {
-    // get the filename from the command args and load the ROM
+    // get the filename and flags from the command args and load the ROM
     let args: Vec<_> = env::args().collect();
-    let filename = &args[1];
+    let json_mode = args.iter().any(|a| a == "--json");
+    let filename = args.iter().skip(1).find(|a| a.as_str() != "--json")
+        .expect("Usage: rustboy9001 <rom path> [--json]");
 
-    let rom_file: Rom = match Rom::from_file_path(filename) {
+    let rom_file: Rom = match Rom::from_archive_path(filename) {
         Err(e) => panic!("Failed to load ROM file. Error message: {:?}", e),
         Ok(rom) => rom,
     };
 
-    // print out all the ROM info
-    println!("\nTitle: {}", rom_file.title);
-    println!("New style cartridge: {}", rom_file.new_cartridge);
-    println!("Manufacturer code: {}", rom_file.manufacturer_code);
-    println!("CGB flag: {:?}", rom_file.cgb_flag);
-    println!("New licensee code: {}", rom_file.new_licensee_code);
-    println!("SGB flag: {:?}", rom_file.sgb_flag);
-    println!("Cartridge type: {:?}", rom_file.cartridge_type);
-    println!("ROM size: {:?}", rom_file.rom_size);
-    println!("RAM size: {:?}", rom_file.ram_size);
-    println!("Destination code: {:?}", rom_file.destination_code);
-    println!("Old licensee code: {:#X}", rom_file.old_licensee_code);
-    println!("Mask ROM version number: {:#X}", rom_file.mask_rom_version_number);
-    println!("Header checksum: {:#X} (valid: {})", rom_file.header_checksum, rom_file.is_header_checksum_valid());
-    println!("Global checksum: {:#X} (valid: {})", rom_file.global_checksum, rom_file.is_global_checksum_valid());
-    println!("Valid Nintendo logo: {}", rom_file.is_nintendo_logo_valid());
+    if json_mode {
+        // emit the parsed header metadata and checksum validity as JSON,
+        // for use in ROM-cataloging pipelines rather than interactive reading
+        println!("{}", serde_json::to_string_pretty(&rom_file.header()).unwrap());
+    } else {
+        // print out all the ROM info
+        println!("\nTitle: {}", rom_file.title);
+        println!("New style cartridge: {}", rom_file.new_cartridge);
+        println!("Manufacturer code: {}", rom_file.manufacturer_code);
+        println!("CGB flag: {:?}", rom_file.cgb_flag);
+        println!("New licensee code: {}", rom_file.new_licensee_code);
+        println!("SGB flag: {:?}", rom_file.sgb_flag);
+        println!("Cartridge type: {:?}", rom_file.cartridge_type);
+        println!("ROM size: {:?}", rom_file.rom_size);
+        println!("RAM size: {:?}", rom_file.ram_size);
+        println!("Destination code: {:?}", rom_file.destination_code);
+        println!("Old licensee code: {:#X}", rom_file.old_licensee_code);
+        println!("Licensee: {}", rom_file.licensee.name);
+        println!("Mask ROM version number: {:#X}", rom_file.mask_rom_version_number);
+        println!("Header checksum: {:#X} (valid: {})", rom_file.header_checksum, rom_file.is_header_checksum_valid());
+        println!("Global checksum: {:#X} (valid: {})", rom_file.global_checksum, rom_file.is_global_checksum_valid());
+        println!("Valid Nintendo logo: {}", rom_file.is_nintendo_logo_valid());
+    }
+
+    // if this cartridge has battery-backed RAM and an existing save file,
+    // make sure it still loads cleanly. This is just a header dump tool with
+    // no emulation loop to mutate RAM, so there's nothing to write back.
+    if rom_file.has_battery() {
+        let save_path = Path::new(filename).with_extension("sav");
+
+        if save_path.exists() {
+            let mut mapper = rom_file.mapper();
+
+            if let Err(e) = rom_file.load_save(&save_path, &mut *mapper) {
+                println!("Failed to load save file: {}", e);
+            }
+        }
+    }
 }
\ No newline at end of file
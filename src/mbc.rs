@@ -0,0 +1,505 @@
+// Memory Bank Controllers -- these sit between the CPU's address bus and the
+// cartridge, remapping the fixed 0x0000-0x7FFF ROM window and the 0xA000-0xBFFF
+// external RAM window onto whichever bank is currently selected.
+
+//
+// Author: Joshua Holmes
+//
+
+use rom::{CartridgeType, RamSize, RomSize};
+
+const ROM_BANK_SIZE: usize = 0x4000;
+
+/// Trait implemented by every cartridge mapper, giving the rest of the
+/// emulator a uniform way to address banked ROM and external RAM.
+pub trait Mbc {
+    /// Reads a byte from the mapper's address space (0x0000-0x7FFF ROM or
+    /// 0xA000-0xBFFF external RAM).
+    fn read(&self, addr: u16) -> u8;
+
+    /// Writes a byte to the mapper's address space. Writes into the ROM
+    /// region typically hit banking registers rather than ROM contents.
+    fn write(&mut self, addr: u16, val: u8);
+
+    /// Returns the external RAM buffer, if this mapper has any.
+    fn ram(&self) -> Option<&[u8]> {
+        None
+    }
+
+    /// Overwrites the external RAM buffer, if this mapper has any.
+    fn load_ram(&mut self, _data: &[u8]) {}
+}
+
+/// No mapper -- a plain 32 KB ROM with no banking and a flat, unbanked
+/// external RAM window (if the cartridge has any).
+pub struct NoMbc {
+    rom_data: Vec<u8>,
+    ram_data: Vec<u8>,
+}
+
+impl NoMbc {
+    fn new(rom_data: Vec<u8>, ram_size: &RamSize) -> NoMbc {
+        NoMbc {
+            rom_data: rom_data,
+            ram_data: vec![0u8; ram_size.num_bytes()],
+        }
+    }
+}
+
+impl Mbc for NoMbc {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000...0x7FFF => *self.rom_data.get(addr as usize).unwrap_or(&0xFF),
+            0xA000...0xBFFF => {
+                if self.ram_data.is_empty() {
+                    0xFF
+                } else {
+                    let len = self.ram_data.len();
+                    self.ram_data[(addr as usize - 0xA000) % len]
+                }
+            }
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        if let 0xA000...0xBFFF = addr {
+            if !self.ram_data.is_empty() {
+                let len = self.ram_data.len();
+                self.ram_data[(addr as usize - 0xA000) % len] = val;
+            }
+        }
+    }
+
+    fn ram(&self) -> Option<&[u8]> {
+        if self.ram_data.is_empty() {
+            None
+        } else {
+            Some(&self.ram_data)
+        }
+    }
+
+    fn load_ram(&mut self, data: &[u8]) {
+        let len = self.ram_data.len().min(data.len());
+        self.ram_data[..len].copy_from_slice(&data[..len]);
+    }
+}
+
+/// MBC1 -- up to 125 switchable ROM banks and up to 4 RAM banks, selected by
+/// a 5-bit ROM bank register and a 2-bit register shared between the RAM
+/// bank and the upper ROM bank bits depending on the banking mode.
+pub struct Mbc1 {
+    rom_data: Vec<u8>,
+    ram_data: Vec<u8>,
+    num_rom_banks: usize,
+    num_ram_banks: usize,
+    ram_enabled: bool,
+    rom_bank_low: u8,
+    bank_upper_bits: u8,
+    ram_banking_mode: bool,
+}
+
+impl Mbc1 {
+    fn new(rom_data: Vec<u8>, rom_size: &RomSize, ram_size: &RamSize) -> Mbc1 {
+        Mbc1 {
+            rom_data: rom_data,
+            ram_data: vec![0u8; ram_size.num_bytes()],
+            num_rom_banks: rom_size.num_banks(),
+            num_ram_banks: ram_size.num_banks(),
+            ram_enabled: false,
+            rom_bank_low: 1,
+            bank_upper_bits: 0,
+            ram_banking_mode: false,
+        }
+    }
+
+    fn rom_bank(&self) -> usize {
+        // only the low 5 bits are checked for zero, so banks 0x20, 0x40 and
+        // 0x60 can't be selected and read as 0x21, 0x41 and 0x61 instead
+        let mut bank = self.rom_bank_low as usize & 0x1F;
+
+        if bank == 0 {
+            bank = 1;
+        }
+
+        if !self.ram_banking_mode {
+            bank |= (self.bank_upper_bits as usize & 0x03) << 5;
+        }
+
+        bank % self.num_rom_banks.max(1)
+    }
+
+    fn ram_bank(&self) -> usize {
+        if self.ram_banking_mode && self.num_ram_banks > 0 {
+            (self.bank_upper_bits as usize & 0x03) % self.num_ram_banks
+        } else {
+            0
+        }
+    }
+
+    /// The size of a single RAM bank in bytes. Usually 0x2000 bytes, but
+    /// `RamSize::Ram2K` carries only one 0x800 bank, so this is derived from
+    /// the actual buffer rather than assumed.
+    fn ram_bank_size(&self) -> usize {
+        self.ram_data.len() / self.num_ram_banks.max(1)
+    }
+}
+
+impl Mbc for Mbc1 {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000...0x3FFF => self.rom_data[addr as usize],
+            0x4000...0x7FFF => {
+                let offset = self.rom_bank() * ROM_BANK_SIZE + (addr as usize - 0x4000);
+                self.rom_data[offset]
+            }
+            0xA000...0xBFFF => {
+                if self.ram_enabled && self.num_ram_banks > 0 {
+                    let bank_size = self.ram_bank_size();
+                    let offset = self.ram_bank() * bank_size + (addr as usize - 0xA000) % bank_size;
+                    self.ram_data[offset]
+                } else {
+                    0xFF
+                }
+            }
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000...0x1FFF => self.ram_enabled = val & 0x0F == 0x0A,
+            0x2000...0x3FFF => self.rom_bank_low = val & 0x1F,
+            0x4000...0x5FFF => self.bank_upper_bits = val & 0x03,
+            0x6000...0x7FFF => self.ram_banking_mode = val & 0x01 == 0x01,
+            0xA000...0xBFFF => {
+                if self.ram_enabled && self.num_ram_banks > 0 {
+                    let bank_size = self.ram_bank_size();
+                    let offset = self.ram_bank() * bank_size + (addr as usize - 0xA000) % bank_size;
+                    self.ram_data[offset] = val;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn ram(&self) -> Option<&[u8]> {
+        if self.num_ram_banks > 0 {
+            Some(&self.ram_data)
+        } else {
+            None
+        }
+    }
+
+    fn load_ram(&mut self, data: &[u8]) {
+        let len = self.ram_data.len().min(data.len());
+        self.ram_data[..len].copy_from_slice(&data[..len]);
+    }
+}
+
+/// MBC2 -- up to 16 switchable ROM banks and 512x4 bits of built-in RAM. RAM
+/// enable and ROM bank selection share the 0x0000-0x3FFF region, split by
+/// bit 8 of the address.
+pub struct Mbc2 {
+    rom_data: Vec<u8>,
+    ram_data: [u8; 512],
+    num_rom_banks: usize,
+    ram_enabled: bool,
+    rom_bank: u8,
+}
+
+impl Mbc2 {
+    fn new(rom_data: Vec<u8>, rom_size: &RomSize) -> Mbc2 {
+        Mbc2 {
+            rom_data: rom_data,
+            ram_data: [0u8; 512],
+            num_rom_banks: rom_size.num_banks(),
+            ram_enabled: false,
+            rom_bank: 1,
+        }
+    }
+
+    fn rom_bank(&self) -> usize {
+        let mut bank = self.rom_bank as usize & 0x0F;
+
+        if bank == 0 {
+            bank = 1;
+        }
+
+        bank % self.num_rom_banks.max(1)
+    }
+}
+
+impl Mbc for Mbc2 {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000...0x3FFF => self.rom_data[addr as usize],
+            0x4000...0x7FFF => {
+                let offset = self.rom_bank() * ROM_BANK_SIZE + (addr as usize - 0x4000);
+                self.rom_data[offset]
+            }
+            0xA000...0xBFFF => {
+                if self.ram_enabled {
+                    self.ram_data[(addr as usize - 0xA000) % 512] | 0xF0
+                } else {
+                    0xFF
+                }
+            }
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000...0x3FFF => {
+                if addr & 0x0100 == 0 {
+                    self.ram_enabled = val & 0x0F == 0x0A;
+                } else {
+                    self.rom_bank = val & 0x0F;
+                }
+            }
+            0xA000...0xBFFF => {
+                if self.ram_enabled {
+                    self.ram_data[(addr as usize - 0xA000) % 512] = val & 0x0F;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn ram(&self) -> Option<&[u8]> {
+        Some(&self.ram_data)
+    }
+
+    fn load_ram(&mut self, data: &[u8]) {
+        let len = self.ram_data.len().min(data.len());
+        self.ram_data[..len].copy_from_slice(&data[..len]);
+    }
+}
+
+/// MBC3 -- up to 128 switchable ROM banks, up to 4 RAM banks, and an
+/// optional real-time clock selected by the same register as the RAM bank.
+pub struct Mbc3 {
+    rom_data: Vec<u8>,
+    ram_data: Vec<u8>,
+    rtc_registers: [u8; 5],
+    num_rom_banks: usize,
+    num_ram_banks: usize,
+    ram_enabled: bool,
+    rom_bank: u8,
+    ram_bank_or_rtc: u8,
+}
+
+impl Mbc3 {
+    fn new(rom_data: Vec<u8>, rom_size: &RomSize, ram_size: &RamSize) -> Mbc3 {
+        Mbc3 {
+            rom_data: rom_data,
+            ram_data: vec![0u8; ram_size.num_bytes()],
+            rtc_registers: [0u8; 5],
+            num_rom_banks: rom_size.num_banks(),
+            num_ram_banks: ram_size.num_banks(),
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank_or_rtc: 0,
+        }
+    }
+
+    fn rom_bank(&self) -> usize {
+        let mut bank = self.rom_bank as usize & 0x7F;
+
+        if bank == 0 {
+            bank = 1;
+        }
+
+        bank % self.num_rom_banks.max(1)
+    }
+
+    /// The size of a single RAM bank in bytes. Usually 0x2000 bytes, but
+    /// `RamSize::Ram2K` carries only one 0x800 bank, so this is derived from
+    /// the actual buffer rather than assumed.
+    fn ram_bank_size(&self) -> usize {
+        self.ram_data.len() / self.num_ram_banks.max(1)
+    }
+}
+
+impl Mbc for Mbc3 {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000...0x3FFF => self.rom_data[addr as usize],
+            0x4000...0x7FFF => {
+                let offset = self.rom_bank() * ROM_BANK_SIZE + (addr as usize - 0x4000);
+                self.rom_data[offset]
+            }
+            0xA000...0xBFFF => {
+                if !self.ram_enabled {
+                    0xFF
+                } else if self.ram_bank_or_rtc <= 0x03 && self.num_ram_banks > 0 {
+                    let bank = self.ram_bank_or_rtc as usize % self.num_ram_banks;
+                    let bank_size = self.ram_bank_size();
+                    self.ram_data[bank * bank_size + (addr as usize - 0xA000) % bank_size]
+                } else if self.ram_bank_or_rtc >= 0x08 && self.ram_bank_or_rtc <= 0x0C {
+                    self.rtc_registers[(self.ram_bank_or_rtc - 0x08) as usize]
+                } else {
+                    0xFF
+                }
+            }
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000...0x1FFF => self.ram_enabled = val & 0x0F == 0x0A,
+            0x2000...0x3FFF => self.rom_bank = val & 0x7F,
+            0x4000...0x5FFF => self.ram_bank_or_rtc = val,
+            0x6000...0x7FFF => (), // latches the RTC; not modeled
+            0xA000...0xBFFF => {
+                if !self.ram_enabled {
+                    return;
+                }
+
+                if self.ram_bank_or_rtc <= 0x03 && self.num_ram_banks > 0 {
+                    let bank = self.ram_bank_or_rtc as usize % self.num_ram_banks;
+                    let bank_size = self.ram_bank_size();
+                    self.ram_data[bank * bank_size + (addr as usize - 0xA000) % bank_size] = val;
+                } else if self.ram_bank_or_rtc >= 0x08 && self.ram_bank_or_rtc <= 0x0C {
+                    self.rtc_registers[(self.ram_bank_or_rtc - 0x08) as usize] = val;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn ram(&self) -> Option<&[u8]> {
+        if self.num_ram_banks > 0 {
+            Some(&self.ram_data)
+        } else {
+            None
+        }
+    }
+
+    fn load_ram(&mut self, data: &[u8]) {
+        let len = self.ram_data.len().min(data.len());
+        self.ram_data[..len].copy_from_slice(&data[..len]);
+    }
+}
+
+/// MBC5 -- up to 512 switchable ROM banks (a full 9-bit bank register) and
+/// up to 16 RAM banks. Unlike MBC1/MBC3, ROM bank 0 is not remapped when
+/// written as the low bank.
+pub struct Mbc5 {
+    rom_data: Vec<u8>,
+    ram_data: Vec<u8>,
+    num_rom_banks: usize,
+    num_ram_banks: usize,
+    ram_enabled: bool,
+    rom_bank: u16,
+    ram_bank: u8,
+}
+
+impl Mbc5 {
+    fn new(rom_data: Vec<u8>, rom_size: &RomSize, ram_size: &RamSize) -> Mbc5 {
+        Mbc5 {
+            rom_data: rom_data,
+            ram_data: vec![0u8; ram_size.num_bytes()],
+            num_rom_banks: rom_size.num_banks(),
+            num_ram_banks: ram_size.num_banks(),
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+        }
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank as usize % self.num_rom_banks.max(1)
+    }
+
+    fn ram_bank(&self) -> usize {
+        if self.num_ram_banks > 0 {
+            self.ram_bank as usize % self.num_ram_banks
+        } else {
+            0
+        }
+    }
+
+    /// The size of a single RAM bank in bytes. Usually 0x2000 bytes, but
+    /// `RamSize::Ram2K` carries only one 0x800 bank, so this is derived from
+    /// the actual buffer rather than assumed.
+    fn ram_bank_size(&self) -> usize {
+        self.ram_data.len() / self.num_ram_banks.max(1)
+    }
+}
+
+impl Mbc for Mbc5 {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000...0x3FFF => self.rom_data[addr as usize],
+            0x4000...0x7FFF => {
+                let offset = self.rom_bank() * ROM_BANK_SIZE + (addr as usize - 0x4000);
+                self.rom_data[offset]
+            }
+            0xA000...0xBFFF => {
+                if self.ram_enabled && self.num_ram_banks > 0 {
+                    let bank_size = self.ram_bank_size();
+                    let offset = self.ram_bank() * bank_size + (addr as usize - 0xA000) % bank_size;
+                    self.ram_data[offset]
+                } else {
+                    0xFF
+                }
+            }
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000...0x1FFF => self.ram_enabled = val & 0x0F == 0x0A,
+            0x2000...0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | val as u16,
+            0x3000...0x3FFF => self.rom_bank = (self.rom_bank & 0x0FF) | ((val as u16 & 0x01) << 8),
+            0x4000...0x5FFF => self.ram_bank = val & 0x0F,
+            0xA000...0xBFFF => {
+                if self.ram_enabled && self.num_ram_banks > 0 {
+                    let bank_size = self.ram_bank_size();
+                    let offset = self.ram_bank() * bank_size + (addr as usize - 0xA000) % bank_size;
+                    self.ram_data[offset] = val;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn ram(&self) -> Option<&[u8]> {
+        if self.num_ram_banks > 0 {
+            Some(&self.ram_data)
+        } else {
+            None
+        }
+    }
+
+    fn load_ram(&mut self, data: &[u8]) {
+        let len = self.ram_data.len().min(data.len());
+        self.ram_data[..len].copy_from_slice(&data[..len]);
+    }
+}
+
+/// Builds the appropriate `Mbc` implementation for the given cartridge type.
+pub fn for_cartridge_type(
+    cartridge_type: &CartridgeType,
+    rom_data: Vec<u8>,
+    rom_size: &RomSize,
+    ram_size: &RamSize,
+) -> Box<Mbc> {
+    use self::CartridgeType::*;
+
+    match *cartridge_type {
+        Mbc1 | Mbc1Ram | Mbc1RamBattery => Box::new(self::Mbc1::new(rom_data, rom_size, ram_size)),
+        Mbc2 | Mbc2Battery => Box::new(self::Mbc2::new(rom_data, rom_size)),
+        Mbc3TimerBattery | Mbc3TimerRamBattery | Mbc3 | Mbc3Ram | Mbc3RamBattery => {
+            Box::new(self::Mbc3::new(rom_data, rom_size, ram_size))
+        }
+        Mbc5 | Mbc5Ram | Mbc5RamBattery | Mbc5Rumble | Mbc5RumbleRam | Mbc5RumbleRamBattery => {
+            Box::new(self::Mbc5::new(rom_data, rom_size, ram_size))
+        }
+        _ => Box::new(NoMbc::new(rom_data, ram_size)),
+    }
+}
@@ -4,13 +4,16 @@
 // Author: Joshua Holmes
 //
 
-use std::error::Error;
 use std::fmt;
 use std::fs::File;
-use std::io::{self, Read};
+use std::io::{self, Read, Write};
 use std::path::Path;
 use std::str;
 
+use zip;
+
+use licensee::LicenseeCode;
+use mbc::{self, Mbc};
 use util;
 
 // Header address constants
@@ -33,6 +36,9 @@ const GLOBAL_CHECKSUM_ADDR: usize = 0x014E;
 /// Flag that says a cartridge is the new format
 const NEW_CARTRIDGE_FLAG: u8 = 0x33;
 
+/// Magic bytes that begin every zip archive, used to detect zipped ROMs
+const ZIP_MAGIC: [u8; 4] = [0x50, 0x4B, 0x03, 0x04];
+
 /// Nintendo logo constant -- the header should contain this
 const VALID_NINTENDO_LOGO: [u8; 48] = 
     [0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
@@ -61,7 +67,7 @@ impl From<String> for RomLoadError {
 }
 
 /// Represents the flags that specify GameBoy Color functionality
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum CgbFlag {
     NoCgb = 0x00,
     SupportsCgb = 0x80,
@@ -82,7 +88,7 @@ impl CgbFlag {
 }
 
 /// Represents the flags that specify Super GameBoy functionality
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum SgbFlag {
     NoSgbSupport = 0x00,
     SgbSupport = 0x03,
@@ -101,7 +107,7 @@ impl SgbFlag {
 }
 
 /// Represents the various cartridge types that exist
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum CartridgeType {
     Rom = 0x00,
     Mbc1 = 0x01,
@@ -178,6 +184,7 @@ impl CartridgeType {
 }
 
 /// Represents the varying amounts of ROM sizes that exist
+#[derive(Clone, Serialize, Deserialize)]
 pub enum RomSize {
     RomBanks0 = 0x00,
     RomBanks4 = 0x01,
@@ -233,7 +240,30 @@ impl fmt::Debug for RomSize {
     }
 }
 
+impl RomSize {
+    /// Returns the total number of 16 KB ROM banks the cartridge carries,
+    /// fixed bank 0 included.
+    pub fn num_banks(&self) -> usize {
+        use self::RomSize::*;
+
+        match *self {
+            RomBanks0 => 2,
+            RomBanks4 => 4,
+            RomBanks8 => 8,
+            RomBanks16 => 16,
+            RomBanks32 => 32,
+            RomBanks64 => 64,
+            RomBanks128 => 128,
+            RomBanks256 => 256,
+            RomBanks72 => 72,
+            RomBanks80 => 80,
+            RomBanks96 => 96,
+        }
+    }
+}
+
 /// Represents the vaying amounts of on-cartridge RAM sizes that exist
+#[derive(Clone, Serialize, Deserialize)]
 pub enum RamSize {
     RamNone = 0x00,
     Ram2K = 0x01,
@@ -274,8 +304,39 @@ impl fmt::Debug for RamSize {
     }
 }
 
+impl RamSize {
+    /// Returns the number of switchable external RAM banks the cartridge
+    /// carries. Banks are 8 KB, except `Ram2K`'s single 2 KB bank.
+    pub fn num_banks(&self) -> usize {
+        use self::RamSize::*;
+
+        match *self {
+            RamNone => 0,
+            Ram2K => 1,
+            Ram8K => 1,
+            Ram32K => 4,
+            Ram64K => 8,
+            Ram128K => 16,
+        }
+    }
+
+    /// Returns the total size, in bytes, of the cartridge's external RAM.
+    pub fn num_bytes(&self) -> usize {
+        use self::RamSize::*;
+
+        match *self {
+            RamNone => 0,
+            Ram2K => 0x800,
+            Ram8K => 0x2000,
+            Ram32K => 0x8000,
+            Ram64K => 0x10000,
+            Ram128K => 0x20000,
+        }
+    }
+}
+
 /// Represents the ROM's destination code
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum DestinationCode {
     Japanese = 0x00,
     NonJapanese = 0x01,
@@ -293,6 +354,30 @@ impl DestinationCode {
     }
 }
 
+/// A snapshot of a `Rom`'s header metadata and checksum validity, suitable
+/// for serializing without dragging along the bulky `rom_data` buffer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RomHeader {
+    pub title: String,
+    pub manufacturer_code: String,
+    pub cgb_flag: CgbFlag,
+    pub new_licensee_code: String,
+    pub sgb_flag: SgbFlag,
+    pub cartridge_type: CartridgeType,
+    pub rom_size: RomSize,
+    pub ram_size: RamSize,
+    pub destination_code: DestinationCode,
+    pub old_licensee_code: u8,
+    pub licensee: LicenseeCode,
+    pub mask_rom_version_number: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+    pub new_cartridge: bool,
+    pub header_checksum_valid: bool,
+    pub global_checksum_valid: bool,
+    pub nintendo_logo_valid: bool,
+}
+
 /// Represents a ROM file and its header metadata
 pub struct Rom {
     pub entry_point: [u8; 4],
@@ -307,6 +392,7 @@ pub struct Rom {
     pub ram_size: RamSize,
     pub destination_code: DestinationCode,
     pub old_licensee_code: u8,
+    pub licensee: LicenseeCode,
     pub mask_rom_version_number: u8,
     pub header_checksum: u8,
     pub global_checksum: u16,
@@ -318,11 +404,7 @@ impl Rom {
     /// Takes in a file path string and returns a Rom
     pub fn from_file_path(filepath: &str) -> Result<Rom, RomLoadError> {
         let path = Path::new(filepath);
-
-        let mut file = match File::open(&path) {
-            Err(e) => panic!("Couldn't open ROM file. Error message: {}", Error::description(&e)),
-            Ok(file) => file,
-        };
+        let mut file = try!(File::open(&path));
 
         Rom::from_file(&mut file)
     }
@@ -332,14 +414,29 @@ impl Rom {
         // read the ROM into a buffer
         let mut buf = Vec::new();
 
-        match file.read_to_end(&mut buf) {
-            Err(e) => panic!("Couldn't read ROM file. Error message: {}", Error::description(&e)),
-            Ok(_) => (),
-        };
+        try!(file.read_to_end(&mut buf));
 
         Rom::from_buffer(buf)
     }
 
+    /// Takes in a file path string, transparently decompressing it first if it's
+    /// a zip archive, and returns a Rom
+    pub fn from_archive_path(filepath: &str) -> Result<Rom, RomLoadError> {
+        let path = Path::new(filepath);
+        let mut file = try!(File::open(&path));
+        let mut buf = Vec::new();
+
+        try!(file.read_to_end(&mut buf));
+
+        let rom_buf = if is_zip_archive(&buf) {
+            try!(extract_rom_from_zip(buf))
+        } else {
+            buf
+        };
+
+        Rom::from_buffer(rom_buf)
+    }
+
     /// Takes in a u8 vector and returns a Rom structure
     pub fn from_buffer(buf: Vec<u8>) -> Result<Rom, RomLoadError> {
         // if the ROM size is less than or equal to the size needed to simply 
@@ -356,8 +453,8 @@ impl Rom {
         let mut entry_point = [0u8; 4];
         let mut nintendo_logo = [0u8; 48];
 
-        util::get_subarray_of_vector(&mut entry_point, &buf, ENTRY_POINT_ADDR);
-        util::get_subarray_of_vector(&mut nintendo_logo, &buf, NINTENDO_LOGO_ADDR);
+        try!(util::get_subarray_of_vector(&mut entry_point, &buf, ENTRY_POINT_ADDR));
+        try!(util::get_subarray_of_vector(&mut nintendo_logo, &buf, NINTENDO_LOGO_ADDR));
 
         // read the enum flags
         let cgb_flag = if new_cartridge { try!(CgbFlag::from_u8(buf[CGB_FLAG_ADDR]).ok_or_else(|| {
@@ -386,19 +483,24 @@ impl Rom {
             format!("Invalid destination code: {:#X}", buf[DESTINATION_CODE_ADDR])
         }));
 
+        let old_licensee_code = buf[OLD_LICENSEE_CODE_ADDR];
+        let new_licensee_code = if new_cartridge { util::bytes_to_string(&buf[NEW_LICENSEE_CODE_ADDR..SGB_FLAG_ADDR]) } else { "".to_owned() };
+        let licensee = LicenseeCode::resolve(old_licensee_code, &new_licensee_code);
+
         Ok(Rom {
             entry_point: entry_point,
             nintendo_logo: nintendo_logo,
-            title: util::bytes_to_string(&buf[TITLE_ADDR..title_end_addr]).to_owned(),
-            manufacturer_code: if new_cartridge { util::bytes_to_string(&buf[MANUFACTURER_CODE_ADDR..CGB_FLAG_ADDR]).to_owned() } else { "".to_owned() },
-            new_licensee_code: if new_cartridge { util::bytes_to_string(&buf[NEW_LICENSEE_CODE_ADDR..SGB_FLAG_ADDR]).to_owned() } else { "".to_owned() },
+            title: util::bytes_to_string(&buf[TITLE_ADDR..title_end_addr]),
+            manufacturer_code: if new_cartridge { util::bytes_to_string(&buf[MANUFACTURER_CODE_ADDR..CGB_FLAG_ADDR]) } else { "".to_owned() },
+            new_licensee_code: new_licensee_code,
             cgb_flag: cgb_flag,
             sgb_flag: sgb_flag,
             cartridge_type: cartridge_type,
             rom_size: rom_size,
             ram_size: ram_size,
             destination_code: destination_code,
-            old_licensee_code: buf[OLD_LICENSEE_CODE_ADDR],
+            old_licensee_code: old_licensee_code,
+            licensee: licensee,
             mask_rom_version_number: buf[MASK_ROM_VERSION_NUMBER_ADDR],
             header_checksum: buf[HEADER_CHECKSUM_ADDR],
             global_checksum: ((buf[GLOBAL_CHECKSUM_ADDR] as u16) << 8) | (buf[GLOBAL_CHECKSUM_ADDR + 1] as u16),
@@ -433,6 +535,100 @@ impl Rom {
 
     /// Says whether the Nintendo logo is valid
     pub fn is_nintendo_logo_valid(&self) -> bool {
-        self.nintendo_logo.iter().zip(VALID_NINTENDO_LOGO.iter()).all(|(a, b)| a == b) 
+        self.nintendo_logo.iter().zip(VALID_NINTENDO_LOGO.iter()).all(|(a, b)| a == b)
+    }
+
+    /// Builds a serializable snapshot of this ROM's header metadata and
+    /// checksum validity, leaving out the bulky `rom_data` buffer.
+    pub fn header(&self) -> RomHeader {
+        RomHeader {
+            title: self.title.clone(),
+            manufacturer_code: self.manufacturer_code.clone(),
+            cgb_flag: self.cgb_flag.clone(),
+            new_licensee_code: self.new_licensee_code.clone(),
+            sgb_flag: self.sgb_flag.clone(),
+            cartridge_type: self.cartridge_type.clone(),
+            rom_size: self.rom_size.clone(),
+            ram_size: self.ram_size.clone(),
+            destination_code: self.destination_code.clone(),
+            old_licensee_code: self.old_licensee_code,
+            licensee: self.licensee.clone(),
+            mask_rom_version_number: self.mask_rom_version_number,
+            header_checksum: self.header_checksum,
+            global_checksum: self.global_checksum,
+            new_cartridge: self.new_cartridge,
+            header_checksum_valid: self.is_header_checksum_valid(),
+            global_checksum_valid: self.is_global_checksum_valid(),
+            nintendo_logo_valid: self.is_nintendo_logo_valid(),
+        }
+    }
+
+    /// Builds the Memory Bank Controller that knows how to address this
+    /// cartridge's ROM and, if it has any, external RAM.
+    pub fn mapper(&self) -> Box<Mbc> {
+        mbc::for_cartridge_type(&self.cartridge_type, self.rom_data.clone(), &self.rom_size, &self.ram_size)
+    }
+
+    /// Says whether this cartridge has battery-backed RAM, meaning its save
+    /// data is worth persisting to a `.sav` file between runs. A mapper whose
+    /// cartridge has no external RAM (such as `Mbc3TimerBattery`, or any header
+    /// declaring `RamNone` outside MBC2) has no RAM buffer, and then
+    /// `write_save`/`load_save` silently do nothing.
+    pub fn has_battery(&self) -> bool {
+        use self::CartridgeType::*;
+
+        match self.cartridge_type {
+            Mbc1RamBattery | Mbc2Battery | RomRamBattery | Mmm01RamBattery |
+            Mbc3TimerBattery | Mbc3TimerRamBattery | Mbc3RamBattery |
+            Mbc4RamBattery | Mbc5RamBattery | Mbc5RumbleRamBattery |
+            Mbc7SensorRumbleRamBattery | HuC1RamBattery => true,
+            _ => false,
+        }
+    }
+
+    /// Reads a `.sav` file from disk into the given mapper's external RAM buffer.
+    pub fn load_save(&self, path: &Path, mapper: &mut Mbc) -> io::Result<()> {
+        let mut file = try!(File::open(path));
+        let mut buf = Vec::new();
+
+        try!(file.read_to_end(&mut buf));
+        mapper.load_ram(&buf);
+
+        Ok(())
+    }
+
+    /// Flushes the given mapper's external RAM buffer to a `.sav` file on disk.
+    pub fn write_save(&self, path: &Path, mapper: &Mbc) -> io::Result<()> {
+        if let Some(ram) = mapper.ram() {
+            let mut file = try!(File::create(path));
+            try!(file.write_all(ram));
+        }
+
+        Ok(())
+    }
+}
+
+/// Says whether the given buffer starts with the zip local file header magic bytes
+fn is_zip_archive(buf: &[u8]) -> bool {
+    buf.len() >= ZIP_MAGIC.len() && &buf[..ZIP_MAGIC.len()] == &ZIP_MAGIC[..]
+}
+
+/// Pulls the first `.gb`/`.gbc` entry out of an in-memory zip archive
+fn extract_rom_from_zip(buf: Vec<u8>) -> Result<Vec<u8>, RomLoadError> {
+    let mut archive = try!(zip::ZipArchive::new(io::Cursor::new(buf)).map_err(|e| {
+        format!("Couldn't read zip archive: {}", e)
+    }));
+
+    for i in 0..archive.len() {
+        let mut entry = try!(archive.by_index(i).map_err(|e| format!("Couldn't read zip entry: {}", e)));
+        let is_rom_entry = entry.name().ends_with(".gb") || entry.name().ends_with(".gbc");
+
+        if is_rom_entry {
+            let mut rom_buf = Vec::new();
+            try!(entry.read_to_end(&mut rom_buf));
+            return Ok(rom_buf);
+        }
     }
+
+    Err(RomLoadError::FormatError("No .gb or .gbc entry found in zip archive".to_owned()))
 }
\ No newline at end of file
@@ -5,23 +5,34 @@
 use std::str;
 
 /// Reads a section of the given vector defined by the range (start..end)
-/// into the given array. 
-pub fn get_subarray_of_vector(mut arr: &mut [u8], vec: &Vec<u8>, start: usize) {
+/// into the given array. Returns an error instead of panicking if that
+/// range runs past the end of the vector.
+pub fn get_subarray_of_vector(arr: &mut [u8], vec: &Vec<u8>, start: usize) -> Result<(), String> {
     let end = start + arr.len();
 
-    if vec.len() < end - 1 {
-        panic!("Error! Attempting to read past the end of a vector");
+    if vec.len() < end {
+        return Err(format!("Attempted to read past the end of a vector: range {}..{}, length {}", start, end, vec.len()));
     }
 
     for (arr_index, vec_index) in (start..end).enumerate() {
         arr[arr_index] = vec[vec_index];
     }
+
+    Ok(())
 }
 
-/// Converts the given u8 slice into a string
-pub fn bytes_to_string(bytes: &[u8]) -> &str {
-    match str::from_utf8(bytes) {
-        Ok(v) => v,
-        Err(e) => panic!("Invalid UTF-8 sequence: {}", e),
+/// Converts the given u8 slice into a string, cutting it off at the first
+/// 0x00 byte so trailing 0x00 padding is dropped. Falls back to a lossy ASCII
+/// decode (non-ASCII bytes become U+FFFD) when the bytes aren't valid UTF-8,
+/// since plenty of real cartridge titles aren't clean UTF-8.
+pub fn bytes_to_string(bytes: &[u8]) -> String {
+    let trimmed = match bytes.iter().position(|&b| b == 0x00) {
+        Some(i) => &bytes[..i],
+        None => bytes,
+    };
+
+    match str::from_utf8(trimmed) {
+        Ok(v) => v.to_owned(),
+        Err(_) => trimmed.iter().map(|&b| if b < 0x80 { b as char } else { '\u{FFFD}' }).collect(),
     }
-}
\ No newline at end of file
+}